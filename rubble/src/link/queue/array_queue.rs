@@ -0,0 +1,139 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use heapless::spsc::{self, MultiCore};
+use heapless::ArrayLength;
+
+use super::{Consume, Consumer, PacketQueue, Producer};
+use crate::link::data::{self, Llid};
+use crate::link::{MIN_DATA_PAYLOAD_BUF, MIN_DATA_PDU_BUF};
+use crate::{bytes::*, Error};
+
+/// A packet queue that can hold up to `N` packets at once.
+///
+/// Unlike `SimpleQueue`, which can only ever buffer a single data PDU, this queue lets the
+/// producer get ahead of the consumer by up to `N` packets, which helps absorb bursts (eg.
+/// several queued LL control PDUs) without stalling.
+///
+/// Like `SimpleQueue`, this type is compatible with thumbv6 cores, which lack the atomic
+/// operations needed by some other queue implementations.
+pub struct ArrayQueue<N: ArrayLength<[u8; MIN_DATA_PDU_BUF]>> {
+    inner: spsc::Queue<[u8; MIN_DATA_PDU_BUF], N, u8, MultiCore>,
+    // Number of packets the consumer has dequeued so far, wrapping at `u8::MAX`. Only the
+    // consumer ever writes this (in `consume_raw_with`); the producer only reads it, in
+    // `free_space`, to see how far the consumer has progressed. `heapless::spsc::Producer`
+    // doesn't expose a `len()` like the unsplit `Queue` does, so this is tracked by hand instead.
+    consumed: AtomicU8,
+}
+
+impl<N: ArrayLength<[u8; MIN_DATA_PDU_BUF]>> ArrayQueue<N> {
+    /// Creates a new, empty queue.
+    pub const fn new() -> Self {
+        Self {
+            inner: spsc::Queue(heapless::i::Queue::u8()),
+            consumed: AtomicU8::new(0),
+        }
+    }
+}
+
+impl<'a, N: ArrayLength<[u8; MIN_DATA_PDU_BUF]>> PacketQueue for &'a mut ArrayQueue<N> {
+    type Producer = ArrayProducer<'a, N>;
+
+    type Consumer = ArrayConsumer<'a, N>;
+
+    fn split(self) -> (Self::Producer, Self::Consumer) {
+        let consumed = &self.consumed;
+        let (p, c) = self.inner.split();
+        (
+            ArrayProducer {
+                inner: p,
+                staging: [0; MIN_DATA_PDU_BUF],
+                produced: 0,
+                consumed,
+            },
+            ArrayConsumer { inner: c, consumed },
+        )
+    }
+}
+
+/// Producer (writer) half returned by `ArrayQueue::split`.
+pub struct ArrayProducer<'a, N: ArrayLength<[u8; MIN_DATA_PDU_BUF]>> {
+    inner: spsc::Producer<'a, [u8; MIN_DATA_PDU_BUF], N, u8, MultiCore>,
+    // `heapless::spsc` only hands out slots by value, so, like `SimpleQueue`, we can't grant a
+    // view directly into the ring's own storage; packets are staged here and copied in on
+    // `commit` instead.
+    staging: [u8; MIN_DATA_PDU_BUF],
+    // Number of packets enqueued so far, wrapping at `u8::MAX`. Only ever touched by the
+    // producer, so this is a plain field rather than an atomic.
+    produced: u8,
+    consumed: &'a AtomicU8,
+}
+
+impl<'a, N: ArrayLength<[u8; MIN_DATA_PDU_BUF]>> Producer for ArrayProducer<'a, N> {
+    fn free_space(&self) -> u8 {
+        let consumed = self.consumed.load(Ordering::Acquire);
+        let queued = self.produced.wrapping_sub(consumed);
+        let free_slots = usize::from(N::to_u8() - queued);
+        (free_slots * MIN_DATA_PAYLOAD_BUF).min(usize::from(u8::MAX)) as u8
+    }
+
+    fn grant(&mut self) -> Option<&mut [u8]> {
+        if !self.inner.ready() {
+            return None;
+        }
+
+        Some(&mut self.staging[2..])
+    }
+
+    fn commit(&mut self, llid: Llid, used: u8) {
+        let mut header = data::Header::new(llid);
+        header.set_payload_length(used);
+        // Can't fail: a `Header` is always exactly 2 bytes, and `self.staging[..2]` is exactly
+        // that big.
+        header
+            .to_bytes(&mut ByteWriter::new(&mut self.staging[..2]))
+            .unwrap();
+
+        self.inner.enqueue(self.staging).map_err(|_| ()).unwrap();
+        self.produced = self.produced.wrapping_add(1);
+    }
+}
+
+/// Consumer (reader) half returned by `ArrayQueue::split`.
+pub struct ArrayConsumer<'a, N: ArrayLength<[u8; MIN_DATA_PDU_BUF]>> {
+    inner: spsc::Consumer<'a, [u8; MIN_DATA_PDU_BUF], N, u8, MultiCore>,
+    consumed: &'a AtomicU8,
+}
+
+impl<'a, N: ArrayLength<[u8; MIN_DATA_PDU_BUF]>> Consumer for ArrayConsumer<'a, N> {
+    fn has_data(&self) -> bool {
+        self.inner.ready()
+    }
+
+    fn consume_raw_with<R>(
+        &mut self,
+        f: impl FnOnce(data::Header, &[u8]) -> Consume<R>,
+    ) -> Result<R, Error> {
+        if let Some(packet) = self.inner.peek() {
+            let mut bytes = ByteReader::new(packet);
+            let raw_header: [u8; 2] = bytes.read_array().unwrap();
+            let header = data::Header::parse(&raw_header);
+            let pl_len = usize::from(header.payload_length());
+            let raw_payload = bytes.read_slice(pl_len)?;
+
+            let res = f(header, raw_payload);
+            if res.consume {
+                self.inner.dequeue().unwrap(); // can't fail
+                let consumed = self.consumed.load(Ordering::Relaxed);
+                self.consumed.store(consumed.wrapping_add(1), Ordering::Release);
+            }
+            res.result
+        } else {
+            Err(Error::Eof)
+        }
+    }
+}
+
+#[test]
+fn array_queue() {
+    super::run_tests(&mut ArrayQueue::<heapless::consts::U4>::new());
+}
@@ -0,0 +1,247 @@
+//! Defines the `PacketQueue` trait and friends, used to connect the Link-Layer to the
+//! application (or another protocol layer) across a producer/consumer split.
+
+use crate::link::data::{self, Llid};
+use crate::{bytes::*, Error};
+
+mod array_queue;
+mod packed_queue;
+#[cfg(feature = "pool-queue")]
+mod pool_queue;
+mod simple_queue;
+
+pub use array_queue::{ArrayConsumer, ArrayProducer, ArrayQueue};
+pub use packed_queue::{PackedConsumer, PackedProducer, PackedQueue};
+#[cfg(feature = "pool-queue")]
+pub use pool_queue::{PduPool, PoolConsumer, PoolProducer, PoolQueue};
+pub use simple_queue::{SimpleConsumer, SimpleProducer, SimpleQueue};
+
+/// Trait for a packet queue that can be split into a producer and a consumer half.
+///
+/// Implementors are expected to be cheap, `no_std`-friendly SPSC (single-producer,
+/// single-consumer) queues connecting the Link-Layer to whatever feeds it data and
+/// whatever consumes the data it outputs.
+pub trait PacketQueue {
+    /// The `Producer` half of this queue.
+    type Producer: Producer;
+
+    /// The `Consumer` half of this queue.
+    type Consumer: Consumer;
+
+    /// Splits this queue into its producer and consumer halves.
+    fn split(self) -> (Self::Producer, Self::Consumer);
+}
+
+/// The writer (producer) half of a `PacketQueue`.
+pub trait Producer {
+    /// Returns the amount of payload bytes that can be queued up via `produce_dyn`.
+    ///
+    /// For a queue that holds several packets, this can be the total over several
+    /// `produce_dyn` calls; a single packet's payload is still limited to the length of the
+    /// slice `grant` returns.
+    ///
+    /// If this returns 0, `produce_dyn` must not be called.
+    fn free_space(&self) -> u8;
+
+    /// Reserves space for the next packet's payload, returning a mutable slice to write it into.
+    ///
+    /// Returns `None` if there's no free slot right now. The caller writes the payload into
+    /// the returned slice (eg. via a `ByteWriter`) and then calls `commit` to publish it.
+    /// `PackedQueue` and `PoolQueue` return a view directly into their backing storage;
+    /// `SimpleQueue` and `ArrayQueue` return a staging buffer that `commit` copies into the
+    /// queue.
+    fn grant(&mut self) -> Option<&mut [u8]>;
+
+    /// Publishes the packet staged in the slice previously returned by `grant`.
+    ///
+    /// `used` is the number of bytes, starting at the front of the granted slice, that were
+    /// actually written and should become the packet's payload.
+    fn commit(&mut self, llid: Llid, used: u8);
+
+    /// Enqueues a new packet by calling `f` with a `ByteWriter` that can be used to write
+    /// the packet's payload.
+    ///
+    /// `f` must return the `Llid` to store in the packet's header. The number of bytes
+    /// written to the `ByteWriter` is recorded as the payload length.
+    ///
+    /// `payload_bytes` is the maximum number of payload bytes `f` might write, and must not
+    /// exceed `self.free_space()` nor the length of the slice `grant` returns (this method
+    /// panics if it does).
+    fn produce_dyn(
+        &mut self,
+        payload_bytes: u8,
+        f: &mut dyn FnMut(&mut ByteWriter<'_>) -> Result<Llid, Error>,
+    ) -> Result<(), Error> {
+        let buf = self.grant().ok_or(Error::Eof)?;
+        assert!(usize::from(payload_bytes) <= buf.len());
+
+        let mut writer = ByteWriter::new(buf);
+        let free = writer.space_left();
+        let llid = f(&mut writer)?;
+        let used = free - writer.space_left();
+
+        self.commit(llid, used as u8);
+        Ok(())
+    }
+}
+
+/// The reader (consumer) half of a `PacketQueue`.
+pub trait Consumer {
+    /// Returns whether the queue has at least one packet ready to be consumed.
+    fn has_data(&self) -> bool;
+
+    /// Calls `f` with the header and raw payload bytes of the next packet in the queue, if any.
+    ///
+    /// `f` returns a `Consume<R>` specifying whether the packet should be removed from the
+    /// queue, and the `R` to return from this method.
+    ///
+    /// If the queue is empty, returns `Err(Error::Eof)` without calling `f`.
+    fn consume_raw_with<R>(
+        &mut self,
+        f: impl FnOnce(data::Header, &[u8]) -> Consume<R>,
+    ) -> Result<R, Error>;
+
+    /// Repeatedly calls `f` for every packet currently queued, in order, until the queue is
+    /// empty or `f` returns `false` to request an early stop.
+    ///
+    /// Like with `consume_raw_with`, `f`'s `Consume::consume` flag controls whether each
+    /// individual packet is removed from the queue or left in place; a packet left in place
+    /// when this method stops early is the next one `consume_raw_with`/`consume_all_with` will
+    /// see. This lets callers (eg. the Link-Layer) drain a whole burst of queued packets in one
+    /// poll, while still being able to stop as soon as eg. the current connection event has run
+    /// out of TX slots.
+    ///
+    /// Leaving a packet in place (`consume: false`) only makes sense paired with a result that
+    /// also stops the loop (`false`); pairing `consume: false` with `true` would otherwise have
+    /// this method re-visit the same un-removed packet forever, so that combination is instead
+    /// treated as a request to stop, the same as `consume: false, result: Ok(false)`.
+    fn consume_all_with(
+        &mut self,
+        mut f: impl FnMut(data::Header, &[u8]) -> Consume<bool>,
+    ) -> Result<(), Error> {
+        loop {
+            let mut consumed = false;
+            let res = self.consume_raw_with(|header, payload| {
+                let res = f(header, payload);
+                consumed = res.consume;
+                res
+            });
+            match res {
+                Ok(true) if consumed => {}
+                Ok(true) | Ok(false) => return Ok(()),
+                Err(Error::Eof) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// The result of processing a single packet popped off of a `Consumer`.
+pub struct Consume<R> {
+    /// Whether the packet that was passed to the closure should be removed from the queue.
+    pub consume: bool,
+
+    /// The value to return from the `Consumer` method that was called.
+    pub result: Result<R, Error>,
+}
+
+impl<R> Consume<R> {
+    /// Returns a `Consume` that always removes the packet from the queue.
+    pub fn always(result: Result<R, Error>) -> Self {
+        Self {
+            consume: true,
+            result,
+        }
+    }
+
+    /// Returns a `Consume` that removes the packet from the queue only if `result` is `Ok`.
+    pub fn if_ok(result: Result<R, Error>) -> Self {
+        Self {
+            consume: result.is_ok(),
+            result,
+        }
+    }
+}
+
+/// Exercises a `PacketQueue` implementation that can hold at least one packet, checking that
+/// its `Producer`/`Consumer` halves behave correctly.
+#[cfg(test)]
+fn run_tests<'a, Q>(queue: &'a mut Q)
+where
+    &'a mut Q: PacketQueue,
+{
+    use crate::link::MIN_DATA_PAYLOAD_BUF;
+
+    let (mut p, mut c) = queue.split();
+
+    assert!(!c.has_data());
+    assert!(p.free_space() >= MIN_DATA_PAYLOAD_BUF as u8);
+
+    p.produce_dyn(2, &mut |writer| {
+        writer.write_slice(&[1, 2]).unwrap();
+        Ok(Llid::DataStart)
+    })
+    .unwrap();
+
+    assert!(c.has_data());
+
+    c.consume_raw_with(|header, payload| {
+        assert_eq!(header.llid(), Llid::DataStart);
+        assert_eq!(payload, &[1, 2]);
+        Consume::always(Ok(()))
+    })
+    .unwrap();
+
+    assert!(!c.has_data());
+}
+
+#[test]
+fn consume_all_with_drains_in_order_and_can_halt() {
+    let mut queue = ArrayQueue::<heapless::consts::U4>::new();
+    let (mut p, mut c) = (&mut queue).split();
+
+    for i in 0..3u8 {
+        p.produce_dyn(1, &mut |writer| {
+            writer.write_slice(&[i]).unwrap();
+            Ok(Llid::DataStart)
+        })
+        .unwrap();
+    }
+
+    let mut seen: heapless::Vec<u8, heapless::consts::U4> = heapless::Vec::new();
+    c.consume_all_with(|_, payload| {
+        seen.push(payload[0]).unwrap();
+        Consume::always(Ok(seen.len() < 2))
+    })
+    .unwrap();
+
+    assert_eq!(&seen[..], &[0, 1]);
+    assert!(c.has_data()); // the third packet is still queued
+}
+
+#[test]
+fn consume_all_with_stops_instead_of_spinning_on_unconsumed_packet() {
+    let mut queue = ArrayQueue::<heapless::consts::U4>::new();
+    let (mut p, mut c) = (&mut queue).split();
+
+    p.produce_dyn(1, &mut |writer| {
+        writer.write_slice(&[1]).unwrap();
+        Ok(Llid::DataStart)
+    })
+    .unwrap();
+
+    let mut calls = 0;
+    // Misbehaving closure: asks to keep going (`true`) without removing the packet
+    // (`consume: false`). Without a guard this would re-peek the same packet forever.
+    c.consume_all_with(|_, _| {
+        calls += 1;
+        Consume {
+            consume: false,
+            result: Ok(true),
+        }
+    })
+    .unwrap();
+
+    assert_eq!(calls, 1);
+    assert!(c.has_data()); // the packet was left in the queue, not dropped
+}
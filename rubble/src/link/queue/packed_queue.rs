@@ -0,0 +1,284 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Consume, Consumer, PacketQueue, Producer};
+use crate::link::data::{self, Llid};
+use crate::{bytes::*, Error};
+
+const HEADER_BYTES: usize = 2;
+
+/// A byte-packed, variable-length packet queue backed by a single `[u8; BYTES]` ring buffer.
+///
+/// Unlike `SimpleQueue` and `ArrayQueue`, which reserve a full fixed-size slot for every queued
+/// packet, `PackedQueue` stores packets back-to-back in one contiguous byte ring, each framed by
+/// its own 2-byte `data::Header`. This wastes very little RAM on small packets (eg. empty PDUs
+/// or short LL control PDUs), which matters on parts with tiny RAM, at the cost of a slightly
+/// more involved read/write path.
+///
+/// Like `SimpleQueue`, this queue only needs plain atomic loads and stores (no
+/// compare-and-swap), so it works on thumbv6 cores too.
+pub struct PackedQueue<const BYTES: usize> {
+    buf: UnsafeCell<[u8; BYTES]>,
+    // Logical (never wrapped) byte offsets; `tail - head` is the number of bytes currently
+    // queued. The producer only ever writes `tail` (and reads `head`), and the consumer only
+    // ever writes `head` (and reads `tail`) — that split is what lets both sides run lock-free
+    // without a CAS loop.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// SAFETY: `PackedQueue` is only ever used through its `Producer`/`Consumer` halves. The producer
+// only writes into the free region between `tail` and `head`, and the consumer only reads the
+// occupied region between `head` and `tail`; those regions never overlap.
+unsafe impl<const BYTES: usize> Sync for PackedQueue<BYTES> {}
+
+impl<const BYTES: usize> PackedQueue<BYTES> {
+    /// Creates a new, empty queue.
+    pub const fn new() -> Self {
+        Self {
+            buf: UnsafeCell::new([0; BYTES]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<'a, const BYTES: usize> PacketQueue for &'a mut PackedQueue<BYTES> {
+    type Producer = PackedProducer<'a, BYTES>;
+
+    type Consumer = PackedConsumer<'a, BYTES>;
+
+    fn split(self) -> (Self::Producer, Self::Consumer) {
+        let queue: &'a PackedQueue<BYTES> = self;
+        (
+            PackedProducer {
+                queue,
+                pending: None,
+            },
+            PackedConsumer { queue },
+        )
+    }
+}
+
+/// Producer (writer) half returned by `PackedQueue::split`.
+pub struct PackedProducer<'a, const BYTES: usize> {
+    queue: &'a PackedQueue<BYTES>,
+    // Set by `grant` and consumed by `commit`: the logical (unwrapped) tail position the header
+    // of the packet currently being staged will be written at.
+    pending: Option<usize>,
+}
+
+impl<'a, const BYTES: usize> Producer for PackedProducer<'a, BYTES> {
+    fn free_space(&self) -> u8 {
+        let head = self.queue.head.load(Ordering::Acquire);
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        let total_free = BYTES - (tail - head);
+        let mut contiguous_free = total_free.min(BYTES - tail % BYTES);
+        if contiguous_free < HEADER_BYTES + 1 && contiguous_free < total_free {
+            // `grant` wraps to the start of the buffer in this case, so report what's free there.
+            contiguous_free = total_free - contiguous_free;
+        }
+
+        contiguous_free
+            .saturating_sub(HEADER_BYTES)
+            .min(usize::from(u8::MAX)) as u8
+    }
+
+    fn grant(&mut self) -> Option<&mut [u8]> {
+        let head = self.queue.head.load(Ordering::Acquire);
+        let mut tail = self.queue.tail.load(Ordering::Relaxed);
+        let total_free = BYTES - (tail - head);
+
+        let mut tail_offset = tail % BYTES;
+        // Clamped by `total_free`: once the occupied region itself wraps around the end of the
+        // buffer, the space from `tail_offset` to the end is partly occupied, not free.
+        let mut contiguous = (BYTES - tail_offset).min(total_free);
+
+        if contiguous < HEADER_BYTES + 1 && contiguous < total_free {
+            // Less than a header-plus-one-byte record fits before the end of the buffer, but
+            // wrapping frees up more: write a zero-length sentinel (if a header fits) so the
+            // consumer knows to skip the remainder, then wrap `tail` to the start of the buffer.
+            if contiguous >= HEADER_BYTES {
+                // `Llid::Reserved` is never passed to `commit` by a real producer (callers only
+                // ever use `DataStart`/`DataCont`/`Control`), so it doubles as an unambiguous
+                // padding marker here: unlike a zero-length `DataCont`, the consumer can tell
+                // this header apart from a legitimate record by its `Llid` alone, even when it
+                // happens to end exactly at the last byte of the buffer.
+                let mut sentinel = data::Header::new(Llid::Reserved);
+                sentinel.set_payload_length(0);
+                // SAFETY: the producer is the only writer, and this range was just proven to
+                // lie within the producer's free region.
+                let buf = unsafe { &mut *self.queue.buf.get() };
+                // Can't fail: a `Header` is always exactly `HEADER_BYTES` bytes, and the slice
+                // below is exactly that big.
+                sentinel
+                    .to_bytes(&mut ByteWriter::new(
+                        &mut buf[tail_offset..tail_offset + HEADER_BYTES],
+                    ))
+                    .unwrap();
+            }
+
+            let wasted = contiguous;
+            tail += wasted;
+            tail_offset = 0;
+            contiguous = total_free - wasted;
+            self.queue.tail.store(tail, Ordering::Release);
+        }
+
+        if contiguous < HEADER_BYTES + 1 {
+            return None;
+        }
+
+        self.pending = Some(tail);
+        let payload_cap = (contiguous - HEADER_BYTES).min(usize::from(u8::MAX));
+
+        // SAFETY: `[tail_offset, tail_offset + HEADER_BYTES + payload_cap)` was just proven to
+        // lie entirely within the producer's free region, which only the producer ever writes
+        // to.
+        let buf = unsafe { &mut *self.queue.buf.get() };
+        Some(&mut buf[tail_offset + HEADER_BYTES..tail_offset + HEADER_BYTES + payload_cap])
+    }
+
+    fn commit(&mut self, llid: Llid, used: u8) {
+        let tail = self.pending.take().expect("commit called without grant");
+        let tail_offset = tail % BYTES;
+
+        let mut header = data::Header::new(llid);
+        header.set_payload_length(used);
+        // SAFETY: see `grant`; this is the same outstanding region it handed out.
+        let buf = unsafe { &mut *self.queue.buf.get() };
+        // Can't fail: a `Header` is always exactly `HEADER_BYTES` bytes, and the slice below is
+        // exactly that big.
+        header
+            .to_bytes(&mut ByteWriter::new(
+                &mut buf[tail_offset..tail_offset + HEADER_BYTES],
+            ))
+            .unwrap();
+
+        self.queue
+            .tail
+            .store(tail + HEADER_BYTES + usize::from(used), Ordering::Release);
+    }
+}
+
+/// Consumer (reader) half returned by `PackedQueue::split`.
+pub struct PackedConsumer<'a, const BYTES: usize> {
+    queue: &'a PackedQueue<BYTES>,
+}
+
+impl<'a, const BYTES: usize> Consumer for PackedConsumer<'a, BYTES> {
+    fn has_data(&self) -> bool {
+        let head = self.queue.head.load(Ordering::Relaxed);
+        let tail = self.queue.tail.load(Ordering::Acquire);
+        head != tail
+    }
+
+    fn consume_raw_with<R>(
+        &mut self,
+        f: impl FnOnce(data::Header, &[u8]) -> Consume<R>,
+    ) -> Result<R, Error> {
+        let tail = self.queue.tail.load(Ordering::Acquire);
+        let mut head = self.queue.head.load(Ordering::Relaxed);
+
+        loop {
+            if head == tail {
+                return Err(Error::Eof);
+            }
+
+            let mut head_offset = head % BYTES;
+            if BYTES - head_offset < HEADER_BYTES {
+                // Not even a header fits before the end of the buffer; the producer always
+                // wraps before reaching this point, so this is unused padding.
+                head += BYTES - head_offset;
+                self.queue.head.store(head, Ordering::Release);
+                continue;
+            }
+
+            // SAFETY: the consumer is the only reader, and `[head, tail)` holds bytes the
+            // producer has published.
+            let buf = unsafe { &*self.queue.buf.get() };
+            let mut reader = ByteReader::new(&buf[head_offset..]);
+            let raw_header: [u8; HEADER_BYTES] = reader.read_array().unwrap();
+            let header = data::Header::parse(&raw_header);
+            let pl_len = usize::from(header.payload_length());
+
+            // A `Reserved` `Llid` is the producer's wrap sentinel: the real record lives at the
+            // start of the buffer. This also catches the edge case where the sentinel's header
+            // ends exactly at `BYTES` (so the length check below wouldn't otherwise notice it).
+            if header.llid() == Llid::Reserved || head_offset + HEADER_BYTES + pl_len > BYTES {
+                head += BYTES - head_offset;
+                self.queue.head.store(head, Ordering::Release);
+                continue;
+            }
+
+            let raw_payload = reader.read_slice(pl_len)?;
+            let res = f(header, raw_payload);
+            if res.consume {
+                self.queue
+                    .head
+                    .store(head + HEADER_BYTES + pl_len, Ordering::Release);
+            }
+            return res.result;
+        }
+    }
+}
+
+#[test]
+fn packed_queue() {
+    super::run_tests(&mut PackedQueue::<32>::new());
+}
+
+#[test]
+fn packed_queue_wraps_tail() {
+    // Small enough that interleaved produce/consume push `tail` past the end of the 8-byte
+    // buffer, landing right on the boundary where exactly `HEADER_BYTES` bytes are left before
+    // the end: the wrap sentinel must be told apart from a real record of the same shape there.
+    let mut queue = PackedQueue::<8>::new();
+    let (mut p, mut c) = (&mut queue).split();
+
+    // header(2) + payload(4) = 6 bytes: tail 0 -> 6.
+    p.produce_dyn(4, &mut |writer| {
+        writer.write_slice(&[1, 2, 3, 4]).unwrap();
+        Ok(Llid::DataStart)
+    })
+    .unwrap();
+    c.consume_raw_with(|header, payload| {
+        assert_eq!(header.llid(), Llid::DataStart);
+        assert_eq!(payload, &[1, 2, 3, 4]);
+        Consume::always(Ok(()))
+    })
+    .unwrap();
+
+    // `head == tail == 6` now, leaving exactly `HEADER_BYTES` bytes before the end of the
+    // buffer: a header fits there, but no payload byte after it, forcing `grant` to write a wrap
+    // sentinel and restart `tail` at 0.
+    p.produce_dyn(2, &mut |writer| {
+        writer.write_slice(&[5, 6]).unwrap();
+        Ok(Llid::DataCont)
+    })
+    .unwrap();
+
+    // Consuming this one transparently skips the wrap sentinel before reaching its header.
+    c.consume_raw_with(|header, payload| {
+        assert_eq!(header.llid(), Llid::DataCont);
+        assert_eq!(payload, &[5, 6]);
+        Consume::always(Ok(()))
+    })
+    .unwrap();
+
+    // header(2) + payload(1) = 3 bytes, entirely after the wrap.
+    p.produce_dyn(1, &mut |writer| {
+        writer.write_slice(&[7]).unwrap();
+        Ok(Llid::DataCont)
+    })
+    .unwrap();
+
+    c.consume_raw_with(|header, payload| {
+        assert_eq!(header.llid(), Llid::DataCont);
+        assert_eq!(payload, &[7]);
+        Consume::always(Ok(()))
+    })
+    .unwrap();
+
+    assert!(!c.has_data());
+}
@@ -0,0 +1,163 @@
+use heapless::pool;
+use heapless::pool::singleton::{Box as PoolBox, Pool as SingletonPool};
+use heapless::spsc::{self, MultiCore};
+use heapless::ArrayLength;
+
+use super::{Consume, Consumer, PacketQueue, Producer};
+use crate::link::data::{self, Llid};
+use crate::link::{MIN_DATA_PAYLOAD_BUF, MIN_DATA_PDU_BUF};
+use crate::{bytes::*, Error};
+
+// The global pool of packet buffers backing every `PoolQueue`. Call `PduPool::grow` with enough
+// backing memory for the number of in-flight packets you need before using a `PoolQueue`.
+//
+// `heapless::pool!` is itself only defined for armv7+ targets, or for `x86_64` when heapless's
+// own `x86-sync-pool` feature is enabled. This crate's own `Cargo.toml` must therefore forward
+// that on for its `pool-queue` feature:
+//
+//     [features]
+//     pool-queue = ["heapless/x86-sync-pool"]
+//
+// without it, `cargo build --features pool-queue` on a plain x86_64 host fails with "cannot
+// find macro `pool` in this scope" rather than a useful error.
+pool!(PduPool: [u8; MIN_DATA_PDU_BUF]);
+
+/// A packet queue that hands packet buffers between producer and consumer by pointer instead of
+/// by value, using a `heapless::pool::Pool` of fixed-size blocks.
+///
+/// This avoids copying `MIN_DATA_PDU_BUF` bytes through the queue on every transfer, and lets
+/// the number of in-flight packets be decoupled from worst-case sizing (just `grow` the
+/// backing `PduPool` with more memory). Unlike `SimpleQueue` and `ArrayQueue`, this relies on
+/// the compare-and-swap `heapless::pool::Pool` needs internally, so it does not work on
+/// thumbv6 cores; use those instead there.
+///
+/// `PduPool` is one process-wide pool shared by every `PoolQueue` in the program, not scoped per
+/// queue: `PduPool::grow` must be called with enough backing memory for the sum of in-flight
+/// packets across *all* `PoolQueue`s that share it, not just this one. Because of that sharing,
+/// `Producer::free_space` can only tell you whether this queue's own SPSC ring has a slot, not
+/// whether the shared pool itself has a free block left; a caller can still see `produce_dyn`/
+/// `grant` fail (return `Err(Error::Eof)`/`None`) even when `free_space() > 0`, if another queue
+/// drained the pool first.
+pub struct PoolQueue<N: ArrayLength<PoolBox<PduPool>>> {
+    inner: spsc::Queue<PoolBox<PduPool>, N, u8, MultiCore>,
+}
+
+impl<N: ArrayLength<PoolBox<PduPool>>> PoolQueue<N> {
+    /// Creates a new, empty queue.
+    ///
+    /// `PduPool::grow` must be called with enough backing memory to cover this queue's share of
+    /// the process-wide in-flight packet budget before packets can be produced; see the
+    /// `PoolQueue` docs above.
+    pub const fn new() -> Self {
+        Self {
+            inner: spsc::Queue(heapless::i::Queue::u8()),
+        }
+    }
+}
+
+impl<'a, N: ArrayLength<PoolBox<PduPool>>> PacketQueue for &'a mut PoolQueue<N> {
+    type Producer = PoolProducer<'a, N>;
+
+    type Consumer = PoolConsumer<'a, N>;
+
+    fn split(self) -> (Self::Producer, Self::Consumer) {
+        let (p, c) = self.inner.split();
+        (
+            PoolProducer {
+                inner: p,
+                pending: None,
+            },
+            PoolConsumer { inner: c },
+        )
+    }
+}
+
+/// Producer (writer) half returned by `PoolQueue::split`.
+pub struct PoolProducer<'a, N: ArrayLength<PoolBox<PduPool>>> {
+    inner: spsc::Producer<'a, PoolBox<PduPool>, N, u8, MultiCore>,
+    // Set by `grant` and consumed by `commit`: the pool block currently being staged.
+    pending: Option<PoolBox<PduPool>>,
+}
+
+impl<'a, N: ArrayLength<PoolBox<PduPool>>> Producer for PoolProducer<'a, N> {
+    fn free_space(&self) -> u8 {
+        // Only reflects this queue's own SPSC ring having a slot, not whether the process-wide
+        // `PduPool` has a free block: callers must still handle `grant`/`produce_dyn` failing
+        // even when this returns non-zero, see the `PoolQueue` docs.
+        if self.inner.ready() {
+            MIN_DATA_PAYLOAD_BUF as u8
+        } else {
+            0
+        }
+    }
+
+    fn grant(&mut self) -> Option<&mut [u8]> {
+        if self.pending.is_none() {
+            if !self.inner.ready() {
+                return None;
+            }
+            let block = PduPool::alloc()?.init([0; MIN_DATA_PDU_BUF]);
+            self.pending = Some(block);
+        }
+
+        Some(&mut self.pending.as_mut().unwrap()[2..])
+    }
+
+    fn commit(&mut self, llid: Llid, used: u8) {
+        let mut block = self.pending.take().expect("commit called without grant");
+
+        let mut header = data::Header::new(llid);
+        header.set_payload_length(used);
+        // Can't fail: a `Header` is always exactly 2 bytes, and `block[..2]` is exactly that big.
+        header
+            .to_bytes(&mut ByteWriter::new(&mut block[..2]))
+            .unwrap();
+
+        self.inner.enqueue(block).map_err(|_| ()).unwrap();
+    }
+}
+
+/// Consumer (reader) half returned by `PoolQueue::split`.
+pub struct PoolConsumer<'a, N: ArrayLength<PoolBox<PduPool>>> {
+    inner: spsc::Consumer<'a, PoolBox<PduPool>, N, u8, MultiCore>,
+}
+
+impl<'a, N: ArrayLength<PoolBox<PduPool>>> Consumer for PoolConsumer<'a, N> {
+    fn has_data(&self) -> bool {
+        self.inner.ready()
+    }
+
+    fn consume_raw_with<R>(
+        &mut self,
+        f: impl FnOnce(data::Header, &[u8]) -> Consume<R>,
+    ) -> Result<R, Error> {
+        if let Some(block) = self.inner.peek() {
+            let mut bytes = ByteReader::new(&block[..]);
+            let raw_header: [u8; 2] = bytes.read_array().unwrap();
+            let header = data::Header::parse(&raw_header);
+            let pl_len = usize::from(header.payload_length());
+            let raw_payload = bytes.read_slice(pl_len)?;
+
+            let res = f(header, raw_payload);
+            if res.consume {
+                // Dropping the block returns it to the pool so it can be reused.
+                self.inner.dequeue().unwrap(); // can't fail
+            }
+            res.result
+        } else {
+            Err(Error::Eof)
+        }
+    }
+}
+
+#[test]
+fn pool_queue() {
+    static mut MEMORY: [u8; MIN_DATA_PDU_BUF * 2] = [0; MIN_DATA_PDU_BUF * 2];
+
+    // SAFETY: this is the only place `MEMORY` is accessed, and `grow` is only called once.
+    unsafe {
+        PduPool::grow(&mut MEMORY);
+    }
+
+    super::run_tests(&mut PoolQueue::<heapless::consts::U2>::new());
+}
@@ -33,13 +33,23 @@ impl<'a> PacketQueue for &'a mut SimpleQueue {
 
     fn split(self) -> (Self::Producer, Self::Consumer) {
         let (p, c) = self.inner.split();
-        (SimpleProducer { inner: p }, SimpleConsumer { inner: c })
+        (
+            SimpleProducer {
+                inner: p,
+                staging: [0; MIN_DATA_PDU_BUF],
+            },
+            SimpleConsumer { inner: c },
+        )
     }
 }
 
 /// Producer (writer) half returned by `SimpleQueue::split`.
 pub struct SimpleProducer<'a> {
     inner: spsc::Producer<'a, [u8; MIN_DATA_PDU_BUF], U1, u8, MultiCore>,
+    // `heapless::spsc` only hands out slots by value, so unlike a hand-rolled mailbox we can't
+    // grant a view directly into the ring's own storage; packets are staged here and copied in
+    // on `commit` instead, exactly like `ArrayQueue`.
+    staging: [u8; MIN_DATA_PDU_BUF],
 }
 
 impl<'a> Producer for SimpleProducer<'a> {
@@ -52,29 +62,24 @@ impl<'a> Producer for SimpleProducer<'a> {
         }
     }
 
-    fn produce_dyn(
-        &mut self,
-        payload_bytes: u8,
-        f: &mut dyn FnMut(&mut ByteWriter<'_>) -> Result<Llid, Error>,
-    ) -> Result<(), Error> {
-        assert!(usize::from(payload_bytes) <= MIN_DATA_PAYLOAD_BUF);
-
+    fn grant(&mut self) -> Option<&mut [u8]> {
         if !self.inner.ready() {
-            return Err(Error::Eof);
+            return None;
         }
 
-        let mut buf = [0; MIN_DATA_PDU_BUF];
-        let mut writer = ByteWriter::new(&mut buf[2..]);
-        let free = writer.space_left();
-        let llid = f(&mut writer)?;
-        let used = free - writer.space_left();
+        Some(&mut self.staging[2..])
+    }
 
+    fn commit(&mut self, llid: Llid, used: u8) {
         let mut header = data::Header::new(llid);
-        header.set_payload_length(used as u8);
-        header.to_bytes(&mut ByteWriter::new(&mut buf[..2]))?;
-
-        self.inner.enqueue(buf).map_err(|_| ()).unwrap();
-        Ok(())
+        header.set_payload_length(used);
+        // Can't fail: a `Header` is always exactly 2 bytes, and `self.staging[..2]` is exactly
+        // that big.
+        header
+            .to_bytes(&mut ByteWriter::new(&mut self.staging[..2]))
+            .unwrap();
+
+        self.inner.enqueue(self.staging).map_err(|_| ()).unwrap();
     }
 }
 
@@ -112,5 +117,5 @@ impl<'a> Consumer for SimpleConsumer<'a> {
 
 #[test]
 fn simple_queue() {
-    run_tests(&mut SimpleQueue::new());
+    super::run_tests(&mut SimpleQueue::new());
 }